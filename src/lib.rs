@@ -0,0 +1,536 @@
+use anyhow::{Context, Error, Result};
+use globset::{Glob, GlobMatcher};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use std::env::{self, current_dir};
+use std::fs::{read_dir, File};
+use std::io::{self, Read};
+use std::path::{self, Component, Path, PathBuf};
+use std::str::FromStr;
+use std::process::Command;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub compile_flags: Option<String>,
+    pub include_paths: Option<Vec<PathBuf>>,
+    pub branches: Vec<Branch>,
+}
+
+impl Config {
+    /// Parse a configuration from a YAML reader.
+    pub fn from_yaml_reader<R: Read>(reader: R) -> Result<Self> {
+        serde_yaml::from_reader(reader).context("problem while parsing YAML configuration")
+    }
+
+    /// Parse a configuration from a JSON reader.
+    pub fn from_json_reader<R: Read>(reader: R) -> Result<Self> {
+        serde_json::from_reader(reader).context("problem while parsing JSON configuration")
+    }
+
+    /// Produce the compilation database in memory. Set `arguments` to emit the
+    /// tokenized `arguments` array form instead of a single `command` string.
+    pub fn into_entries(&self, arguments: bool) -> Result<Vec<CLangEntry>> {
+        let compile_flags = self.compile_flags.clone().unwrap_or_else(String::new);
+        let include_paths = self.include_paths.clone().unwrap_or_else(Vec::new);
+        let mut db = Vec::new();
+        for branch in &self.branches {
+            db.extend(branch.clone().create_clangd_entry(
+                compile_flags.clone(),
+                include_paths.clone(),
+                arguments,
+            )?);
+        }
+        Ok(db)
+    }
+}
+
+/// Serialize a compilation database to the given file as pretty JSON.
+pub fn write_entries(entries: &[CLangEntry], file: &Path) -> Result<()> {
+    let out = File::create(file).context("unable to create file for output generation")?;
+    serde_json::to_writer_pretty(&out, entries)
+        .context("problem while serializing clangd compilation database")?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Branch {
+    pub branch: String,
+    pub compile_flags: Option<String>,
+    pub include_paths: Option<Vec<PathBuf>>,
+    pub mask: Option<Vec<String>>,
+    pub tool: Option<String>,
+    /// Explicitly select the discovery backend by name (`extension`, `mask`, or
+    /// `tool`); when omitted it is inferred from the `mask`/`tool` fields.
+    pub backend: Option<String>,
+}
+
+/// A strategy for discovering the source files contributed by a single candidate
+/// directory. Additional backends (e.g. a `git ls-files` or `build.ninja` reader)
+/// can be added by implementing this trait without touching the core scan loop.
+pub trait DiscoveryBackend {
+    fn discover(&self, candidate: &Path) -> Result<Vec<PathBuf>>;
+}
+
+/// Scan a candidate directory for the trivial C/C++ source extensions.
+pub struct ExtensionScan;
+
+impl DiscoveryBackend for ExtensionScan {
+    fn discover(&self, candidate: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        scan_files(
+            candidate,
+            &["*.c", "*.C", "*.cc", "*.cpp", "*.cxx", "*.c++"],
+            &mut files,
+        );
+        Ok(files)
+    }
+}
+
+/// Scan a candidate directory with a configured set of glob masks.
+pub struct MaskScan {
+    masks: Vec<String>,
+}
+
+impl DiscoveryBackend for MaskScan {
+    fn discover(&self, candidate: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let masks = self.masks.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        scan_files(candidate, &masks, &mut files);
+        Ok(files)
+    }
+}
+
+/// Run an external tool in the candidate directory and treat its stdout as a
+/// whitespace-separated list of source files.
+pub struct ToolOutput {
+    tool: String,
+}
+
+impl DiscoveryBackend for ToolOutput {
+    fn discover(&self, candidate: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut elements = self.tool.split_whitespace();
+        let cmd: Option<&str> = elements.next();
+        let arguments: Vec<&str> = elements.collect();
+        if let Some(cmd) = cmd {
+            // execute a tool in candidate directory location
+            let cmd = Command::new(cmd)
+                .args(arguments)
+                .current_dir(candidate)
+                .output()
+                .with_context(|| format!("unable to execute tool '{}'", self.tool))?;
+            if !cmd.status.success() {
+                return Err(Error::msg(format!(
+                    "'{}' returned error: {}\n---stdout:\n{}\n---stderr:\n{}",
+                    self.tool,
+                    cmd.status.code().unwrap_or(0),
+                    String::from_utf8_lossy(&cmd.stdout),
+                    String::from_utf8_lossy(&cmd.stderr),
+                )));
+            }
+            // isolate file-names from response
+            for filename in String::from_utf8_lossy(&cmd.stdout)
+                .split_whitespace()
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+            {
+                let mut file;
+                if filename.starts_with("/") {
+                    // appears to be a full path
+                    file = PathBuf::from(filename);
+                } else {
+                    // appears a fragment so assume the relative location from execution
+                    // point
+                    file = PathBuf::from(candidate);
+                    file.push(filename);
+                }
+                // only consider existing files
+                if file.exists() {
+                    files.push(file);
+                }
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+impl Branch {
+    fn create_clangd_entry(
+        mut self,
+        mut flags: String,
+        mut paths: Vec<PathBuf>,
+        arguments: bool,
+    ) -> Result<Vec<CLangEntry>> {
+        let cur_dir = current_dir()?;
+
+        // expand `${VAR}`/`${CWD}` references before the pattern and paths are interpreted
+        self.branch = substitute(&self.branch, &cur_dir)?;
+
+        if let Some(path::MAIN_SEPARATOR) = self.branch.chars().next() {
+        } else {
+            // put current path to configured branch glob
+            self.branch = format!(
+                "{}{}{}",
+                cur_dir.display(),
+                path::MAIN_SEPARATOR,
+                self.branch
+            );
+        }
+
+        let glob = Glob::new(&self.branch)
+            .with_context(|| {
+                format!(
+                    "unable to parse '{}' as 'branch' as a glob pattern",
+                    self.branch
+                )
+            })?
+            .compile_matcher();
+        if let Some(compile_flags) = self.compile_flags.take() {
+            if flags.len() > 0 {
+                flags.push(' ');
+            }
+            flags.push_str(&compile_flags);
+        }
+        if let Some(include_paths) = self.include_paths.take() {
+            paths.extend(include_paths);
+        }
+
+        // expand variables in flags and include paths before resolution
+        flags = substitute(&flags, &cur_dir)?;
+        for path in paths.iter_mut() {
+            *path = PathBuf::from(substitute(&path.display().to_string(), &cur_dir)?);
+        }
+
+        let mut candidates = Vec::new();
+        find_directories(&cur_dir, &glob, &mut candidates);
+
+        let backend = self.backend()?;
+
+        // discover sources per candidate directory in parallel; subprocess execution and
+        // directory scans are independent, so this is where the scan spends its wall-clock.
+        let files = candidates
+            .par_iter()
+            .map(|candidate| backend.discover(candidate))
+            .collect::<Result<Vec<_>>>()?;
+
+        // canonicalize and deduplicate the whole file list once, rather than re-resolving
+        // inside the per-file loop, so the result is stable regardless of scan order.
+        let mut files = files
+            .into_iter()
+            .flatten()
+            .map(|file| {
+                file.canonicalize()
+                    .with_context(|| format!("unable to resolve '{}'", file.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        files.sort();
+        files.dedup();
+
+        let mut db_items = Vec::new();
+
+        for file_path in files {
+            let directory = file_path
+                .parent()
+                .map(|d| d.to_path_buf())
+                .ok_or_else(|| Error::msg("unable to find parent directory for file"))?;
+            let file = file_path
+                .file_name()
+                .and_then(|f| f.to_str())
+                .map(|f| f.to_string())
+                .ok_or_else(|| Error::msg("unable to find file-name"))?;
+            let mut object_file = file_path.clone();
+            object_file.set_extension("o");
+            let object_file = object_file
+                .file_name()
+                .and_then(|f| f.to_str())
+                .map(|f| f.to_string())
+                .ok_or_else(|| Error::msg("unable to find file-name"))?;
+
+            // resolve paths for every file iteration (bug-fix where only the first file gets
+            // resolved).
+            let mut paths = paths.clone();
+
+            for path in paths.iter_mut() {
+                let first_component = path.components().next();
+
+                // don't touch paths when they are a root-dir or a windows driver letter
+                if !matches!(first_component, Some(Component::RootDir) | Some(Component::Prefix(_)))
+                {
+                    let mut new;
+                    // use execution location as reference when no "." or ".." are used
+                    if matches!(first_component, Some(Component::Normal(_))) {
+                        new = cur_dir.clone();
+                    } else {
+                        // when "." or ".." are used, the path relative from file location
+                        new = file_path.clone();
+                        new.pop();
+                    }
+                    let error_path = new.clone(); // just to produce a better error message (if any)
+                    new.push(&path);
+                    *path = new.canonicalize().with_context(|| {
+                        format!(
+                            "unable to resolve '{}' from '{}'",
+                            path.display(),
+                            error_path.display()
+                        )
+                    })?;
+                }
+            }
+
+            let exe = resolve_executable(&file_path)?;
+
+            // build the tokenized argv for this invocation: the compiler, one element per
+            // include path, the caller-supplied compile flags, and finally the compilation
+            // of the source into its object file.
+            let mut argv = vec![exe];
+            argv.extend(paths.iter().map(|p| format!("-I{}", p.display())));
+            argv.extend(flags.split_whitespace().map(|f| f.to_string()));
+            argv.push("-c".to_string());
+            argv.push("-o".to_string());
+            argv.push(object_file.clone());
+            argv.push(file.clone());
+
+            let (command, arguments, output) = if arguments {
+                (None, Some(argv), Some(object_file))
+            } else {
+                (Some(argv.join(" ")), None, None)
+            };
+
+            db_items.push(CLangEntry {
+                directory,
+                file,
+                command,
+                arguments,
+                output,
+            });
+        }
+
+        // produce a reproducible database across runs regardless of scan order
+        db_items.sort_by(|a, b| a.directory.cmp(&b.directory).then_with(|| a.file.cmp(&b.file)));
+
+        Ok(db_items)
+    }
+
+    /// Select the discovery backend for this branch. An explicit `backend` name wins;
+    /// otherwise it is inferred from the `mask`/`tool` fields, defaulting to the
+    /// trivial C/C++ extension scan.
+    fn backend(&self) -> Result<Box<dyn DiscoveryBackend + Sync>> {
+        // an explicit name always wins; otherwise infer from the configured fields
+        let selected = match self.backend.as_deref() {
+            Some("extension") => "extension",
+            Some("mask") => "mask",
+            Some("tool") => "tool",
+            Some(other) => return Err(Error::msg(format!("unknown discovery backend '{}'", other))),
+            None if self.mask.is_some() => "mask",
+            None if self.tool.is_some() => "tool",
+            None => "extension",
+        };
+
+        match selected {
+            "mask" => {
+                let masks = self.mask.clone().ok_or_else(|| {
+                    Error::msg("'mask' backend selected but no 'mask' globs configured")
+                })?;
+                Ok(Box::new(MaskScan { masks }))
+            }
+            "tool" => {
+                let tool = self.tool.clone().ok_or_else(|| {
+                    Error::msg("'tool' backend selected but no 'tool' command configured")
+                })?;
+                Ok(Box::new(ToolOutput { tool }))
+            }
+            _ => Ok(Box::new(ExtensionScan)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CLangEntry {
+    pub directory: PathBuf,
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// Configuration serialization format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "json" => Ok(Format::Json),
+            other => Err(Error::msg(format!("unknown format '{}'", other))),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "simple-clangd-gen")]
+pub struct Opt {
+    /// Configuration file (YAML or JSON), or `-` to read from stdin
+    #[structopt(name = "INPUT", parse(from_os_str))]
+    pub input: PathBuf,
+    /// Generated JSON Compilation Database Format Specification
+    #[structopt(name = "OUTPUT", parse(from_os_str))]
+    pub output: PathBuf,
+    /// Emit the tokenized `arguments` array (plus `output`) instead of a single
+    /// `command` string, so paths containing spaces survive re-splitting
+    #[structopt(long = "arguments")]
+    pub arguments: bool,
+    /// Force the configuration parser; required when reading from stdin where the
+    /// file extension is unavailable
+    #[structopt(long = "format", possible_values = &["yaml", "json"])]
+    pub format: Option<Format>,
+}
+
+/// Drive the tool from parsed options, returning any error instead of exiting.
+pub fn run(opt: Opt) -> Result<()> {
+    let from_stdin = opt.input.as_os_str() == "-";
+
+    // determine the parser: an explicit `--format` always wins, otherwise fall back
+    // to sniffing the file extension (impossible when reading from stdin).
+    let format = match opt.format {
+        Some(format) => format,
+        None if from_stdin => {
+            return Err(Error::msg("reading from stdin requires an explicit --format"));
+        }
+        None => match opt.input.extension() {
+            Some(e) if e == "yml" || e == "yaml" => Format::Yaml,
+            Some(e) if e == "json" => Format::Json,
+            _ => return Err(Error::msg("only yaml/json files are supported")),
+        },
+    };
+
+    let reader: Box<dyn Read> = if from_stdin {
+        Box::new(io::stdin())
+    } else {
+        Box::new(
+            File::open(&opt.input)
+                .with_context(|| format!("unable to open file `{}`", opt.input.display()))?,
+        )
+    };
+
+    let source = if from_stdin {
+        "<stdin>".to_string()
+    } else {
+        opt.input.display().to_string()
+    };
+
+    let conf = match format {
+        Format::Yaml => Config::from_yaml_reader(reader),
+        Format::Json => Config::from_json_reader(reader),
+    }
+    .with_context(|| format!("parsing error in `{}`", source))?;
+
+    let entries = conf.into_entries(opt.arguments)?;
+    write_entries(&entries, &opt.output)?;
+    Ok(())
+}
+
+/// Find directories that matches the given path and matcher.
+/// It ignores any error and tries to return the best as possible result.
+fn find_directories(dir: &Path, matcher: &GlobMatcher, candidates: &mut Vec<PathBuf>) {
+    if let Ok(mut dir) = read_dir(dir) {
+        while let Some(Ok(entry)) = dir.next() {
+            if let Ok(file_type) = entry.file_type() {
+                if file_type.is_dir() && !file_type.is_symlink() {
+                    let path = entry.path();
+                    if matcher.is_match(&path) {
+                        candidates.push(path);
+                    } else {
+                        find_directories(&path, matcher, candidates);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Expand `${...}` references in `input`. `${CWD}` resolves to the execution
+/// directory; every other name is looked up in the process environment and an
+/// unset variable is an error so configs fail loudly rather than silently dropping
+/// an include root.
+fn substitute(input: &str, cur_dir: &Path) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| Error::msg(format!("unterminated '${{' in '{}'", input)))?;
+        let name = &after[..end];
+
+        let value = match name {
+            "CWD" => cur_dir.display().to_string(),
+            _ => env::var(name).with_context(|| {
+                format!(
+                    "environment variable '{}' referenced in '{}' is not set",
+                    name, input
+                )
+            })?,
+        };
+        out.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+fn scan_files(path: &Path, masks: &[&str], files: &mut Vec<PathBuf>) {
+    let masks: Vec<_> = masks
+        .iter()
+        .filter_map(|d| Glob::new(d).ok())
+        .map(|d| d.compile_matcher())
+        .collect();
+
+    if let Ok(mut dir) = read_dir(path) {
+        while let Some(Ok(entry)) = dir.next() {
+            if masks.iter().any(|m| m.is_match(entry.file_name())) {
+                files.push(entry.path());
+            }
+        }
+    }
+}
+
+fn resolve_executable(source_file: &Path) -> Result<String> {
+    let is_c = source_file.extension().map(|s| s == "c").unwrap_or(false);
+
+    let candidates = match is_c {
+        false => &["clang++", "g++", "c++"],
+        true => &["clang", "gcc", "cc"],
+    };
+
+    let paths = env::var_os("PATH")
+        .ok_or_else(|| Error::msg("unable to resolve PATH environment variable"))?;
+
+    for candidate in candidates {
+        for mut path in env::split_paths(&paths) {
+            path.push(candidate);
+            if path.exists() {
+                return Ok(format!("{}", path.display()));
+            }
+        }
+    }
+
+    Err(Error::msg(format!(
+        "unable to locate a compiler for '{}'",
+        source_file.display()
+    )))
+}